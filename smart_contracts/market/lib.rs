@@ -6,6 +6,7 @@ use ink_lang as ink;
 mod matchingengine {
     use ink_storage::{
         collections::HashMap as HashMap,
+        collections::Vec as StorageVec,
         traits::{
             PackedLayout,
             SpreadLayout,
@@ -23,6 +24,7 @@ mod matchingengine {
         coll_token_id: u128,
         #[ink(topic)]
         price: Balance,
+        royalty: Balance,
     }
 
     /// Withdraw types.
@@ -48,7 +50,7 @@ mod matchingengine {
 
         /////////////////////////////////////////////////////////////////////////////////
         // Deposits / Balances / Withdrawals
-        
+
         /// Balances by quote currency ID and address
         /// Quote currency ID:
         /// 1 = Unique (not live yet, so unused for now)
@@ -87,10 +89,66 @@ mod matchingengine {
 
         /// Last Ask ID
         last_ask_id: u128,
+
+        /////////////////////////////////////////////////////////////////////////////////
+        // Bids
+
+        /// Current bids: bid_id -> (collectionId, tokenId, quote_id, price, buyer)
+        bids: HashMap<u128, (u64, u64, u64, Balance, AccountId)>,
+
+        /// Bid index: Helps find every standing bid for a colectionId + tokenId,
+        /// ordered by placement (ascending bid_id gives time priority)
+        bids_by_token: HashMap<(u64, u64), StorageVec<u128>>,
+
+        /// Last Bid ID
+        last_bid_id: u128,
+
+        /////////////////////////////////////////////////////////////////////////////////
+        // Fees
+
+        /// Maker/taker commission by quote currency ID, in basis points (1/100th of a percent)
+        fees: HashMap<u64, (u16, u16)>,
+
+        /// Commission collected so far, by quote currency ID, pending owner withdrawal
+        fees_collected: HashMap<u64, Balance>,
+
+        /////////////////////////////////////////////////////////////////////////////////
+        // Royalties
+
+        /// Per-collection royalty: collection_id -> (recipient, basis points of the sale price)
+        royalties: HashMap<u64, (AccountId, u16)>,
+
+        /////////////////////////////////////////////////////////////////////////////////
+        // Conversion rates
+
+        /// Conversion rate to the native currency by quote currency ID, fixed-point scaled by
+        /// `RATE_SCALE`. A quote currency with no entry here cannot be used for new asks or bids;
+        /// an ask already standing in it can still be bought.
+        conversion_rate_to_native: HashMap<u64, Balance>,
+
+        /////////////////////////////////////////////////////////////////////////////////
+        // Approvals
+
+        /// Operator approved to manage a deposited token on the deposit owner's behalf
+        approvals: HashMap<(u64, u64), AccountId>,
+
+        /////////////////////////////////////////////////////////////////////////////////
+        // Account registration / dust protection
+
+        /// Accounts that have explicitly registered and may hold balances/asks/bids
+        registered: HashMap<AccountId, bool>,
+
+        /// Count of active references (non-zero balances, open asks, open bids, un-asked NFT
+        /// deposits) held by an account; once this drops to zero the account is dust-pruned
+        /// from `registered`
+        active_refs: HashMap<AccountId, u32>,
     }
 
     impl MatchingEngine {
 
+        /// Fixed-point scale used by `conversion_rate_to_native` (1.0 == `RATE_SCALE`)
+        const RATE_SCALE: Balance = 1_000_000_000_000_000_000;
+
         #[ink(constructor)]
         pub fn new() -> Self {
             let mut total_traded = HashMap::new();
@@ -109,6 +167,16 @@ mod matchingengine {
                 asks: HashMap::new(),
                 asks_by_token: HashMap::new(),
                 last_ask_id: 0,
+                bids: HashMap::new(),
+                bids_by_token: HashMap::new(),
+                last_bid_id: 0,
+                fees: HashMap::new(),
+                fees_collected: HashMap::new(),
+                royalties: HashMap::new(),
+                conversion_rate_to_native: HashMap::new(),
+                approvals: HashMap::new(),
+                registered: HashMap::new(),
+                active_refs: HashMap::new(),
             }
 
         }
@@ -131,7 +199,7 @@ mod matchingengine {
         pub fn get_total(&self, quote_id: u64) -> Balance {
             *self.total_traded.get(&quote_id).unwrap()
         }
-        
+
         /// Reset total
         #[ink(message)]
         pub fn reset_total(&mut self, quote_id: u64) {
@@ -139,17 +207,90 @@ mod matchingengine {
             self.total_traded.insert(quote_id, 0);
         }
 
+        /// Owner: Set the conversion rate to native currency for a quote currency ID
+        /// (fixed-point, scaled by `RATE_SCALE`)
+        #[ink(message)]
+        pub fn set_conversion_rate(&mut self, quote_id: u64, rate: Balance) {
+            self.ensure_only_owner();
+            self.conversion_rate_to_native.insert(quote_id, rate);
+        }
+
+        /// Owner: Remove the conversion rate for a quote currency ID, disabling new asks and bids
+        /// in it (standing asks in it can still be bought)
+        #[ink(message)]
+        pub fn remove_conversion_rate(&mut self, quote_id: u64) {
+            self.ensure_only_owner();
+            let _ = self.conversion_rate_to_native.take(&quote_id);
+        }
+
+        /// Get total traded volume across all quote currencies, normalized to native currency
+        #[ink(message)]
+        pub fn get_total_normalized(&self) -> Balance {
+            let mut total: Balance = 0;
+            for (quote_id, traded) in self.total_traded.iter() {
+                if let Some(rate) = self.conversion_rate_to_native.get(quote_id) {
+                    let normalized = traded.saturating_mul(*rate) / Self::RATE_SCALE;
+                    total = total.saturating_add(normalized);
+                }
+            }
+            total
+        }
+
+        /// Owner: Set the maker/taker commission (in basis points) charged on trades in a quote currency
+        #[ink(message)]
+        pub fn set_fee(&mut self, quote_id: u64, maker_bps: u16, taker_bps: u16) {
+            self.ensure_only_owner();
+            assert!((maker_bps as u32) + (taker_bps as u32) <= 10_000);
+            self.fees.insert(quote_id, (maker_bps, taker_bps));
+        }
+
+        /// Get commission collected so far (pending withdrawal) for a quote currency
+        #[ink(message)]
+        pub fn get_fees_collected(&self, quote_id: u64) -> Balance {
+            *self.fees_collected.get(&quote_id).unwrap_or(&0)
+        }
+
+        /// Owner: Withdraw collected commission for a quote currency
+        ///
+        /// Fees never pass through the owner's spendable `quote_balance`, so this queues the
+        /// vault withdraw directly instead of going through `vault_withdraw` (which checks and
+        /// debits that balance).
+        #[ink(message)]
+        pub fn withdraw_fees(&mut self, quote_id: u64) {
+            self.ensure_only_owner();
+            let collected = *self.fees_collected.get(&quote_id).unwrap_or(&0);
+            self.fees_collected.insert(quote_id, 0);
+
+            self.last_withdraw_id = self.last_withdraw_id + 1;
+            self.withdraw_queue.insert(self.last_withdraw_id, (self.owner.clone(), collected, WithdrawType::WithdrawMatched));
+        }
+
+        /// Admin: Set the creator royalty (in basis points of the sale price) for a collection
+        #[ink(message)]
+        pub fn set_royalty(&mut self, collection_id: u64, recipient: AccountId, bps: u16) {
+            self.ensure_only_admin();
+            assert!(bps <= 10_000);
+            self.royalties.insert(collection_id, (recipient, bps));
+        }
+
+        /// User: Register so the market may hold balances, asks and bids on your behalf
+        #[ink(message)]
+        pub fn register_account(&mut self) {
+            self.registered.insert(self.env().caller(), true);
+        }
+
         /// Admin: Make a deposit for a user
         #[ink(message)]
         pub fn register_deposit(&mut self, quote_id: u64, deposit_balance: Balance, user: AccountId) {
             self.ensure_only_admin();
+            self.ensure_registered(&user);
 
             // Check overflow
             let initial_balance = self.balance_of_or_zero(quote_id, &user);
             assert!(initial_balance + deposit_balance > initial_balance);
 
             // Set or update quote balance
-            self.quote_balance.insert((quote_id, user.clone()), initial_balance + deposit_balance);
+            self.set_balance(quote_id, &user, initial_balance + deposit_balance);
         }
 
         /// Get address balance in quote currency
@@ -161,7 +302,9 @@ mod matchingengine {
         /// User: Withdraw funds
         #[ink(message)]
         pub fn withdraw(&mut self, quote_id: u64, withdraw_balance: Balance) {
-            self.vault_withdraw(&self.env().caller(), quote_id, withdraw_balance, WithdrawType::WithdrawUnused);
+            let caller = self.env().caller();
+            self.vault_withdraw(&caller, quote_id, withdraw_balance, WithdrawType::WithdrawUnused);
+            self.maybe_deregister(&caller);
         }
 
         /// Get last withdraw id
@@ -196,35 +339,75 @@ mod matchingengine {
 
             // Record the token deposit for the user
             self.nft_deposits.insert((collection_id, token_id), user.clone());
+
+            // An un-asked deposit is itself an active reference, so a user who is given a
+            // deposit and never touches their balance isn't dust-pruned out from under it
+            self.touch_account(&user);
         }
 
-        /// Get deposit 
+        /// Get deposit
         #[ink(message)]
         pub fn get_nft_deposit(&self, collection_id: u64, token_id: u64) -> AccountId {
             *self.nft_deposits.get(&(collection_id, token_id)).unwrap()
         }
 
+        /// Deposit owner: Approve an operator to list/cancel this deposited token on their behalf
+        #[ink(message)]
+        pub fn approve(&mut self, collection_id: u64, token_id: u64, operator: AccountId) {
+            let deposit_owner = self.current_deposit_owner(collection_id, token_id);
+            assert_eq!(self.env().caller(), deposit_owner);
+            self.approvals.insert((collection_id, token_id), operator);
+        }
+
+        /// Deposit owner: Revoke a previously approved operator for this deposited token
+        #[ink(message)]
+        pub fn revoke_approval(&mut self, collection_id: u64, token_id: u64) {
+            let deposit_owner = self.current_deposit_owner(collection_id, token_id);
+            assert_eq!(self.env().caller(), deposit_owner);
+            let _ = self.approvals.take(&(collection_id, token_id));
+        }
+
+        /// Get the operator approved for a deposited token
+        #[ink(message)]
+        pub fn get_approved(&self, collection_id: u64, token_id: u64) -> AccountId {
+            *self.approvals.get(&(collection_id, token_id)).unwrap()
+        }
+
         /// User: Place a deposited NFT for sale
         #[ink(message)]
         pub fn ask(&mut self, collection_id: u64, token_id: u64, quote_id: u64, price: Balance) {
 
-            // make sure sender owns this deposit (if not called by the admin)
+            // Quote currency must have a registered conversion rate to be tradeable
+            assert!(self.conversion_rate_to_native.get(&quote_id).is_some());
+
+            // make sure sender owns this deposit, or is the contract owner or an approved operator
             let deposit_owner = *self.nft_deposits.get(&(collection_id, token_id)).unwrap();
-            if self.env().caller() != (*self).owner {
-                assert_eq!(deposit_owner, self.env().caller());
-            }
+            assert!(self.is_authorized_for_token(collection_id, token_id, &deposit_owner));
+            self.ensure_registered(&deposit_owner);
 
-            // Remove a deposit
+            // Remove a deposit; the deposit's active reference is replaced by the ask's below
             let _ = self.nft_deposits.take(&(collection_id, token_id));
+            self.untouch_account(&deposit_owner);
 
             // Place an ask (into asks with a new Ask ID)
             let ask_id = self.last_ask_id + 1;
             let ask = (collection_id, token_id, quote_id, price, deposit_owner.clone());
             self.last_ask_id = ask_id;
             self.asks.insert(ask_id, ask.clone());
+            self.touch_account(&deposit_owner);
 
             // Record that token is being sold by this user (in asks_by_token) in reverse lookup index
             self.asks_by_token.insert((collection_id, token_id), ask_id);
+
+            // Try to settle immediately against the best standing bid for this token, in this quote currency
+            if let Some((bid_id, bid_price, buyer)) = self.best_bid_for_token(collection_id, token_id, quote_id) {
+                if bid_price >= price {
+                    // The bid rested first, so it sets the settlement price
+                    self.remove_ask(collection_id, token_id, ask_id);
+                    self.remove_bid(collection_id, token_id, bid_id);
+                    self.finalize_trade(collection_id, token_id, quote_id, bid_price, bid_price, deposit_owner, buyer);
+                }
+            }
         }
 
         /// Get last ask ID
@@ -249,12 +432,10 @@ mod matchingengine {
         #[ink(message)]
         pub fn cancel(&mut self, collection_id: u64, token_id: u64) {
 
-            // Ensure that sender owns this ask
+            // Ensure that sender owns this ask, or is the contract owner or an approved operator
             let ask_id = *self.asks_by_token.get(&(collection_id, token_id)).unwrap();
             let (_, _, _, _, user) = *self.asks.get(&ask_id).unwrap();
-            if self.env().caller() != self.owner {
-                assert_eq!(self.env().caller(), user);
-            }
+            assert!(self.is_authorized_for_token(collection_id, token_id, &user));
 
             // Remove ask from everywhere
             self.remove_ask(collection_id, token_id, ask_id);
@@ -262,6 +443,8 @@ mod matchingengine {
             // Transfer token back to user through NFT Vault
             self.last_nft_withdraw_id = self.last_nft_withdraw_id + 1;
             self.nft_withdraw_queue.insert(self.last_nft_withdraw_id, (user, collection_id, token_id));
+
+            self.maybe_deregister(&user);
         }
 
         /// Match an ask
@@ -271,41 +454,114 @@ mod matchingengine {
             // Get the ask
             let ask_id = *self.asks_by_token.get(&(collection_id, token_id)).unwrap();
             let (_, _, quote_id, price, seller) = *self.asks.get(&ask_id).unwrap();
+            let buyer = self.env().caller();
+            self.ensure_registered(&buyer);
 
             // Check that buyer has enough balance
-            let initial_buyer_balance = self.balance_of_or_zero(quote_id, &self.env().caller());
+            let initial_buyer_balance = self.balance_of_or_zero(quote_id, &buyer);
             assert!(initial_buyer_balance >= price);
-            
+
             // Subtract balance from buyer and increase balance of the seller and owner (due to commission)
-            let initial_seller_balance = self.balance_of_or_zero(quote_id, &seller);
-            assert!(initial_seller_balance + price > initial_seller_balance); // overflow protection
-            self.quote_balance.insert((quote_id, self.env().caller().clone()), initial_buyer_balance - price);
-            self.quote_balance.insert((quote_id, seller.clone()), initial_seller_balance + price);
+            self.set_balance(quote_id, &buyer, initial_buyer_balance - price);
+            let (net_to_seller, royalty) = self.settle_proceeds(collection_id, quote_id, price, &seller);
 
             // Remove ask from everywhere
             self.remove_ask(collection_id, token_id, ask_id);
 
             // Start an NFT withdraw from the vault
             self.last_nft_withdraw_id = self.last_nft_withdraw_id + 1;
-            self.nft_withdraw_queue.insert(self.last_nft_withdraw_id, (self.env().caller().clone(), collection_id, token_id));
+            self.nft_withdraw_queue.insert(self.last_nft_withdraw_id, (buyer.clone(), collection_id, token_id));
 
             // Start Quote withdraw from the vault for the seller
-            self.vault_withdraw(&seller, quote_id, price, WithdrawType::WithdrawMatched);
+            self.vault_withdraw(&seller, quote_id, net_to_seller, WithdrawType::WithdrawMatched);
 
             // Update totals
-            let total = *self.total_traded.get(&quote_id).unwrap();
+            let total = *self.total_traded.get(&quote_id).unwrap_or(&0);
             self.total_traded.insert(quote_id, total + price);
 
+            self.maybe_deregister(&buyer);
+            self.maybe_deregister(&seller);
+
             // Emit Sold event
             let ctid : u128 = (collection_id as u128) * 0x100000000 + (token_id as u128);
             Self::env().emit_event(Sold {
                 seller: Some(seller),
-                buyer: Some(self.env().caller()),
+                buyer: Some(buyer),
                 coll_token_id: ctid,
                 price: price,
+                royalty: royalty,
             });
         }
 
+        /// User: Place a bid on a token, escrowing the bid amount out of the free balance
+        #[ink(message)]
+        pub fn place_bid(&mut self, collection_id: u64, token_id: u64, quote_id: u64, price: Balance) {
+
+            // Quote currency must have a registered conversion rate to be tradeable
+            assert!(self.conversion_rate_to_native.get(&quote_id).is_some());
+
+            let buyer = self.env().caller();
+            self.ensure_registered(&buyer);
+
+            // Escrow the bid amount so it cannot be spent twice
+            let initial_balance = self.balance_of_or_zero(quote_id, &buyer);
+            assert!(initial_balance >= price);
+            self.set_balance(quote_id, &buyer, initial_balance - price);
+
+            // Record the bid
+            let bid_id = self.last_bid_id + 1;
+            self.last_bid_id = bid_id;
+            self.bids.insert(bid_id, (collection_id, token_id, quote_id, price, buyer.clone()));
+            self.touch_account(&buyer);
+
+            let mut token_bids = self.bids_by_token.take(&(collection_id, token_id)).unwrap_or_default();
+            token_bids.push(bid_id);
+            self.bids_by_token.insert((collection_id, token_id), token_bids);
+
+            // Try to settle immediately against the standing ask for this token
+            if let Some(ask_id) = self.asks_by_token.get(&(collection_id, token_id)) {
+                let (_, _, ask_quote_id, ask_price, seller) = *self.asks.get(ask_id).unwrap();
+                if ask_quote_id == quote_id && ask_price <= price {
+                    let ask_id = *ask_id;
+                    // The ask rested first, so it sets the settlement price
+                    self.remove_ask(collection_id, token_id, ask_id);
+                    self.remove_bid(collection_id, token_id, bid_id);
+                    self.finalize_trade(collection_id, token_id, quote_id, ask_price, price, seller, buyer);
+                }
+            }
+        }
+
+        /// Get last bid ID
+        #[ink(message)]
+        pub fn get_last_bid_id(&self) -> u128 {
+            self.last_bid_id
+        }
+
+        /// Get bid by ID
+        #[ink(message)]
+        pub fn get_bid_by_id(&self, bid_id: u128) -> (u64, u64, u64, Balance, AccountId) {
+            *self.bids.get(&bid_id).unwrap()
+        }
+
+        /// Cancel a bid, releasing the escrowed balance back to the bidder
+        #[ink(message)]
+        pub fn cancel_bid(&mut self, bid_id: u128) {
+
+            let (collection_id, token_id, quote_id, price, buyer) = *self.bids.get(&bid_id).unwrap();
+            if self.env().caller() != self.owner {
+                assert_eq!(self.env().caller(), buyer);
+            }
+
+            // Remove bid from everywhere
+            self.remove_bid(collection_id, token_id, bid_id);
+
+            // Release the escrowed balance back to the bidder
+            let balance = self.balance_of_or_zero(quote_id, &buyer);
+            self.set_balance(quote_id, &buyer, balance + price);
+
+            self.maybe_deregister(&buyer);
+        }
+
         /// Panic if the sender is not the contract owner
         fn ensure_only_owner(&self) {
             assert_eq!(self.env().caller(), self.owner);
@@ -321,12 +577,238 @@ mod matchingengine {
             *self.quote_balance.get(&(quote_id, *user)).unwrap_or(&0)
         }
 
+        /// Panic if the account hasn't called `register_account`
+        fn ensure_registered(&self, user: &AccountId) {
+            assert!(*self.registered.get(user).unwrap_or(&false));
+        }
+
+        /// Record a new active reference (non-zero balance, open ask, open bid or un-asked NFT
+        /// deposit) for `user`
+        fn touch_account(&mut self, user: &AccountId) {
+            let count = *self.active_refs.get(user).unwrap_or(&0);
+            self.active_refs.insert((*user).clone(), count + 1);
+        }
+
+        /// Release an active reference for `user`. Does not itself decide deregistration, since
+        /// a single message (e.g. a matched trade) may untouch and re-touch the same account
+        /// several times before settling on a final state; call `maybe_deregister` once the
+        /// message is done mutating `user`'s state.
+        fn untouch_account(&mut self, user: &AccountId) {
+            let count = *self.active_refs.get(user).unwrap_or(&0);
+            if count <= 1 {
+                let _ = self.active_refs.take(user);
+            } else {
+                self.active_refs.insert((*user).clone(), count - 1);
+            }
+        }
+
+        /// Dust-prune `user` from `registered` if they end up with no active references
+        /// (balances, open asks, open bids, un-asked NFT deposits) left
+        fn maybe_deregister(&mut self, user: &AccountId) {
+            if self.active_refs.get(user).is_none() {
+                let _ = self.registered.take(user);
+            }
+        }
+
+        /// Set `user`'s quote balance, deleting the storage entry instead of leaving a zero
+        /// behind, and keeping `active_refs` in sync. Does not touch `registered`; that is left to
+        /// `maybe_deregister`.
+        fn set_balance(&mut self, quote_id: u64, user: &AccountId, balance: Balance) {
+            let existed = self.quote_balance.get(&(quote_id, *user)).is_some();
+            if balance == 0 {
+                if existed {
+                    let _ = self.quote_balance.take(&(quote_id, *user));
+                    self.untouch_account(user);
+                }
+            } else {
+                if !existed {
+                    self.touch_account(user);
+                }
+                self.quote_balance.insert((quote_id, (*user).clone()), balance);
+            }
+        }
+
+        /// Split `price` into the protocol commission (added to `fees_collected`, claimable by
+        /// the owner via `withdraw_fees`), the creator royalty for `collection_id` (credited to
+        /// its recipient), and the seller's net proceeds. Returns `(net_to_seller, royalty_amount)`.
+        ///
+        /// Both the commission and the royalty are taken as a slice of the full `price`; the
+        /// combined basis points are asserted not to exceed 100% so the two can never combine
+        /// to exceed the full sale price, even though `set_fee` and `set_royalty` only cap
+        /// themselves individually.
+        fn settle_proceeds(&mut self, collection_id: u64, quote_id: u64, price: Balance, seller: &AccountId) -> (Balance, Balance) {
+            let (maker_bps, taker_bps) = *self.fees.get(&quote_id).unwrap_or(&(0, 0));
+            let fee_bps = (maker_bps as u32) + (taker_bps as u32);
+            let royalty_bps = match self.royalties.get(&collection_id) {
+                Some((_, bps)) => *bps as u32,
+                None => 0,
+            };
+            assert!(fee_bps + royalty_bps <= 10_000);
+
+            let fee = price * (fee_bps as Balance) / 10_000;
+
+            if fee > 0 {
+                // The fee is only ever a claim against `fees_collected`, never also parked in
+                // the owner's spendable `quote_balance` - otherwise it would be reachable
+                // through both an ordinary `withdraw` and `withdraw_fees`.
+                let collected = *self.fees_collected.get(&quote_id).unwrap_or(&0);
+                self.fees_collected.insert(quote_id, collected + fee);
+            }
+
+            let royalty = match self.royalties.get(&collection_id) {
+                Some((recipient, bps)) => {
+                    let royalty = price * (*bps as Balance) / 10_000;
+                    if royalty > 0 {
+                        let recipient = recipient.clone();
+                        let recipient_balance = self.balance_of_or_zero(quote_id, &recipient);
+                        self.set_balance(quote_id, &recipient, recipient_balance + royalty);
+                    }
+                    royalty
+                }
+                None => 0,
+            };
+
+            let net = price - fee - royalty;
+            let seller_balance = self.balance_of_or_zero(quote_id, seller);
+            assert!(seller_balance + net >= seller_balance); // overflow protection
+            self.set_balance(quote_id, seller, seller_balance + net);
+            (net, royalty)
+        }
+
         fn remove_ask(&mut self, collection_id: u64, token_id: u64, ask_id: u128) {
+            // An ask is an active reference for its seller
+            if let Some((_, _, _, _, seller)) = self.asks.get(&ask_id) {
+                let seller = seller.clone();
+                self.untouch_account(&seller);
+            }
+
             // Remove the record that token is being sold by this user (from asks_by_token)
             let _ = self.asks_by_token.take(&(collection_id, token_id));
 
             // Remove an ask (from asks)
             let _ = self.asks.take(&ask_id);
+
+            // An approval only grants control over the current listing; clear it on transfer
+            let _ = self.approvals.take(&(collection_id, token_id));
+        }
+
+        /// The account that currently controls a deposited token: the depositor, or (once
+        /// listed) the ask's seller.
+        fn current_deposit_owner(&self, collection_id: u64, token_id: u64) -> AccountId {
+            if let Some(owner) = self.nft_deposits.get(&(collection_id, token_id)) {
+                return *owner;
+            }
+            let ask_id = *self.asks_by_token.get(&(collection_id, token_id)).unwrap();
+            let (_, _, _, _, seller) = *self.asks.get(&ask_id).unwrap();
+            seller
+        }
+
+        /// Whether the caller may manage `collection_id`/`token_id` on behalf of `owner`:
+        /// the contract owner, the deposit owner themselves, or their approved operator.
+        fn is_authorized_for_token(&self, collection_id: u64, token_id: u64, owner: &AccountId) -> bool {
+            let caller = self.env().caller();
+            if caller == self.owner || caller == *owner {
+                return true;
+            }
+            match self.approvals.get(&(collection_id, token_id)) {
+                Some(operator) => caller == *operator,
+                None => false,
+            }
+        }
+
+        /// Find the best standing bid for a token in a given quote currency: highest price,
+        /// earliest (lowest) bid ID on ties. Bids in other quote currencies don't cross this
+        /// ask/bid - their balances aren't comparable - so they're filtered out up front rather
+        /// than just gating on the result.
+        fn best_bid_for_token(&self, collection_id: u64, token_id: u64, quote_id: u64) -> Option<(u128, Balance, AccountId)> {
+            let bid_ids = self.bids_by_token.get(&(collection_id, token_id))?;
+
+            let mut best: Option<(u128, Balance, AccountId)> = None;
+            for bid_id in bid_ids.iter() {
+                let (_, _, bid_quote_id, price, buyer) = *self.bids.get(bid_id).unwrap();
+                if bid_quote_id != quote_id {
+                    continue;
+                }
+                let is_better = match &best {
+                    None => true,
+                    Some((best_id, best_price, _)) => price > *best_price || (price == *best_price && *bid_id < *best_id),
+                };
+                if is_better {
+                    best = Some((*bid_id, price, buyer));
+                }
+            }
+            best
+        }
+
+        fn remove_bid(&mut self, collection_id: u64, token_id: u64, bid_id: u128) {
+            // A bid is an active reference for its buyer
+            if let Some((_, _, _, _, buyer)) = self.bids.get(&bid_id) {
+                let buyer = buyer.clone();
+                self.untouch_account(&buyer);
+            }
+
+            // Remove the bid from the per-token index
+            if let Some(bid_ids) = self.bids_by_token.take(&(collection_id, token_id)) {
+                let mut remaining = StorageVec::new();
+                for id in bid_ids.iter() {
+                    if *id != bid_id {
+                        remaining.push(*id);
+                    }
+                }
+                if !remaining.is_empty() {
+                    self.bids_by_token.insert((collection_id, token_id), remaining);
+                }
+            }
+
+            // Remove the bid (from bids)
+            let _ = self.bids.take(&bid_id);
+        }
+
+        /// Settle a matched ask/bid pair. `escrowed` is the amount locked on the matched bid
+        /// (equal to `settle_price` when the ask triggered the match); any excess is refunded.
+        fn finalize_trade(
+            &mut self,
+            collection_id: u64,
+            token_id: u64,
+            quote_id: u64,
+            settle_price: Balance,
+            escrowed: Balance,
+            seller: AccountId,
+            buyer: AccountId,
+        ) {
+            // Refund the bidder any escrow above the settlement price
+            if escrowed > settle_price {
+                let refund = escrowed - settle_price;
+                let buyer_balance = self.balance_of_or_zero(quote_id, &buyer);
+                self.set_balance(quote_id, &buyer, buyer_balance + refund);
+            }
+
+            // Increase balance of the seller, net of the protocol commission and creator royalty
+            let (net_to_seller, royalty) = self.settle_proceeds(collection_id, quote_id, settle_price, &seller);
+
+            // Start an NFT withdraw from the vault for the buyer
+            self.last_nft_withdraw_id = self.last_nft_withdraw_id + 1;
+            self.nft_withdraw_queue.insert(self.last_nft_withdraw_id, (buyer.clone(), collection_id, token_id));
+
+            // Start Quote withdraw from the vault for the seller
+            self.vault_withdraw(&seller, quote_id, net_to_seller, WithdrawType::WithdrawMatched);
+
+            // Update totals
+            let total = *self.total_traded.get(&quote_id).unwrap_or(&0);
+            self.total_traded.insert(quote_id, total + settle_price);
+
+            self.maybe_deregister(&buyer);
+            self.maybe_deregister(&seller);
+
+            // Emit Sold event
+            let ctid : u128 = (collection_id as u128) * 0x100000000 + (token_id as u128);
+            Self::env().emit_event(Sold {
+                seller: Some(seller),
+                buyer: Some(buyer),
+                coll_token_id: ctid,
+                price: settle_price,
+                royalty: royalty,
+            });
         }
 
         fn vault_withdraw(&mut self, user: &AccountId, quote_id: u64, withdraw_balance: Balance, withdraw_type: WithdrawType) {
@@ -335,7 +817,7 @@ mod matchingengine {
             assert!(initial_balance >= withdraw_balance);
 
             // Update user's quote balance
-            self.quote_balance.insert((quote_id, (*user).clone()), initial_balance - withdraw_balance);
+            self.set_balance(quote_id, user, initial_balance - withdraw_balance);
 
             // Increase last withdraw index
             self.last_withdraw_id = self.last_withdraw_id + 1;
@@ -346,4 +828,3 @@ mod matchingengine {
 
     }
 }
-